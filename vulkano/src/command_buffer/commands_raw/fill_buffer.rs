@@ -0,0 +1,217 @@
+// Copyright (c) 2016 The vulkano developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use std::error;
+use std::fmt;
+use std::sync::Arc;
+
+use buffer::Buffer;
+use buffer::BufferAccess;
+use buffer::BufferInner;
+use command_buffer::CommandAddError;
+use command_buffer::cb::AddCommand;
+use command_buffer::cb::UnsafeCommandBufferBuilder;
+use command_buffer::pool::CommandPool;
+use device::Device;
+use device::DeviceOwned;
+use VulkanObject;
+use VulkanPointers;
+use vk;
+
+/// Command that fills a buffer with repetitions of a 32-bit value.
+pub struct CmdFillBuffer<B> {
+    // The buffer to fill.
+    buffer: B,
+    // Raw buffer handle.
+    buffer_handle: vk::Buffer,
+    // Offset of the fill.
+    offset: vk::DeviceSize,
+    // Size of the fill, or `vk::WHOLE_SIZE` to fill up to the end of the buffer.
+    size: vk::DeviceSize,
+    // The 32-bit pattern repeated at every word of the filled range.
+    data: u32,
+}
+
+impl<B> CmdFillBuffer<B> {
+    /// Builds a command that fills a buffer with a 32-bit value repeated over its length.
+    ///
+    /// If `size` is `None`, the whole buffer starting at `offset` is filled, using
+    /// `VK_WHOLE_SIZE` when the buffer reaches the end of its underlying allocation. The offset
+    /// and size must be multiples of four; with `None`, the remaining size from `offset` to the
+    /// end of the buffer must be a multiple of four.
+    #[inline]
+    pub fn new<P>(buffer: P, offset: usize, size: Option<usize>, data: u32)
+                  -> Result<CmdFillBuffer<B>, CmdFillBufferError>
+        where P: Buffer<Access = B>,
+              B: BufferAccess
+    {
+        unsafe {
+            CmdFillBuffer::unchecked_type(buffer.access(), offset, size, data)
+        }
+    }
+
+    /// Same as `new`, except that the parameter is a `BufferAccess` instead of a `Buffer`.
+    #[inline]
+    pub fn from_access(buffer: B, offset: usize, size: Option<usize>, data: u32)
+                        -> Result<CmdFillBuffer<B>, CmdFillBufferError>
+        where B: BufferAccess
+    {
+        unsafe {
+            CmdFillBuffer::unchecked_type(buffer, offset, size, data)
+        }
+    }
+
+    /// Same as `from_access`, except that type safety is not enforced.
+    pub unsafe fn unchecked_type(buffer: B, offset: usize, size: Option<usize>, data: u32)
+                                  -> Result<CmdFillBuffer<B>, CmdFillBufferError>
+        where B: BufferAccess
+    {
+        let buffer_size = buffer.size();
+
+        let (buffer_handle, inner_offset, buffer_inner_size) = {
+            let BufferInner { buffer: buffer_inner, offset: inner_offset } = buffer.inner();
+            if !buffer_inner.usage_transfer_dest() {
+                return Err(CmdFillBufferError::BufferMissingUsage);
+            }
+            if inner_offset % 4 != 0 {
+                return Err(CmdFillBufferError::WrongAlignment);
+            }
+            (buffer_inner.internal_object(), inner_offset, buffer_inner.size())
+        };
+
+        if offset % 4 != 0 {
+            return Err(CmdFillBufferError::WrongAlignment);
+        }
+
+        // The VUID for `vkCmdFillBuffer` requires `offset` to be strictly less than the
+        // destination buffer's size, so `offset == buffer_size` (a degenerate zero-size fill
+        // at the very end of the buffer) is rejected too.
+        if offset >= buffer_size {
+            return Err(CmdFillBufferError::OutOfBounds);
+        }
+
+        let absolute_offset = inner_offset.checked_add(offset)
+                                           .ok_or(CmdFillBufferError::OutOfBounds)?;
+
+        let vk_size = match size {
+            Some(size) => {
+                if size % 4 != 0 {
+                    return Err(CmdFillBufferError::WrongAlignment);
+                }
+                let end = offset.checked_add(size).ok_or(CmdFillBufferError::OutOfBounds)?;
+                if end > buffer_size {
+                    return Err(CmdFillBufferError::OutOfBounds);
+                }
+                // Also bound the fill by the underlying allocation's real size, not just this
+                // `BufferAccess`'s own declared view, in case the two differ.
+                let absolute_end = absolute_offset.checked_add(size)
+                                                   .ok_or(CmdFillBufferError::OutOfBounds)?;
+                if absolute_end > buffer_inner_size {
+                    return Err(CmdFillBufferError::OutOfBounds);
+                }
+                size as vk::DeviceSize
+            },
+            None => {
+                let remaining = buffer_size - offset;
+                if remaining % 4 != 0 {
+                    return Err(CmdFillBufferError::WrongAlignment);
+                }
+                let absolute_end = absolute_offset.checked_add(remaining)
+                                                   .ok_or(CmdFillBufferError::OutOfBounds)?;
+                if absolute_end > buffer_inner_size {
+                    return Err(CmdFillBufferError::OutOfBounds);
+                }
+                // `VK_WHOLE_SIZE` fills up to the end of the underlying allocation, so it can only
+                // be used when this view reaches that end; otherwise pass the view's remaining size.
+                if absolute_end == buffer_inner_size {
+                    vk::WHOLE_SIZE
+                } else {
+                    remaining as vk::DeviceSize
+                }
+            },
+        };
+
+        Ok(CmdFillBuffer {
+            buffer: buffer,
+            buffer_handle: buffer_handle,
+            offset: absolute_offset as vk::DeviceSize,
+            size: vk_size,
+            data: data,
+        })
+    }
+
+    /// Returns the buffer that is going to be filled.
+    #[inline]
+    pub fn buffer(&self) -> &B {
+        &self.buffer
+    }
+}
+
+unsafe impl<B> DeviceOwned for CmdFillBuffer<B>
+    where B: DeviceOwned
+{
+    #[inline]
+    fn device(&self) -> &Arc<Device> {
+        self.buffer.device()
+    }
+}
+
+unsafe impl<'a, P, B> AddCommand<&'a CmdFillBuffer<B>> for UnsafeCommandBufferBuilder<P>
+    where B: BufferAccess,
+          P: CommandPool,
+{
+    type Out = UnsafeCommandBufferBuilder<P>;
+
+    #[inline]
+    fn add(self, command: &'a CmdFillBuffer<B>) -> Result<Self::Out, CommandAddError> {
+        unsafe {
+            let vk = self.device().pointers();
+            let cmd = self.internal_object();
+            vk.CmdFillBuffer(cmd, command.buffer_handle, command.offset, command.size,
+                              command.data);
+        }
+
+        Ok(self)
+    }
+}
+
+/// Error that can happen when creating a `CmdFillBuffer`.
+#[derive(Debug, Copy, Clone)]
+pub enum CmdFillBufferError {
+    /// The "transfer destination" usage must be enabled on the buffer.
+    BufferMissingUsage,
+    /// The offset or size are not aligned to 4 bytes.
+    WrongAlignment,
+    /// The offset and size are out of the buffer's range.
+    OutOfBounds,
+}
+
+impl error::Error for CmdFillBufferError {
+    #[inline]
+    fn description(&self) -> &str {
+        match *self {
+            CmdFillBufferError::BufferMissingUsage => {
+                "the transfer destination usage must be enabled on the buffer"
+            },
+            CmdFillBufferError::WrongAlignment => {
+                "the offset or size are not aligned to 4 bytes"
+            },
+            CmdFillBufferError::OutOfBounds => {
+                "the offset and size are out of the buffer's range"
+            },
+        }
+    }
+}
+
+impl fmt::Display for CmdFillBufferError {
+    #[inline]
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(fmt, "{}", error::Error::description(self))
+    }
+}
@@ -9,16 +9,21 @@
 
 use std::error;
 use std::fmt;
+use std::mem;
+use std::ptr;
 use std::sync::Arc;
 
 use buffer::Buffer;
 use buffer::BufferAccess;
+use buffer::BufferUsage;
 use buffer::TypedBuffer;
 use buffer::TypedBufferAccess;
 use buffer::BufferInner;
+use buffer::cpu_access::CpuAccessibleBuffer;
 use command_buffer::CommandAddError;
 use command_buffer::cb::AddCommand;
 use command_buffer::cb::UnsafeCommandBufferBuilder;
+use command_buffer::commands_raw::CmdCopyBuffer;
 use command_buffer::pool::CommandPool;
 use device::Device;
 use device::DeviceOwned;
@@ -26,6 +31,55 @@ use VulkanObject;
 use VulkanPointers;
 use vk;
 
+/// The maximum number of bytes that `vkCmdUpdateBuffer` can write in a single call.
+const UPDATE_BUFFER_MAX_SIZE: usize = 65536;
+
+/// Shared validation for `CmdUpdateBuffer` and `CmdUpdateBufferPadded`: checks the buffer's
+/// usage and alignment, and that `size` bytes fit both within `buffer`'s own declared view and
+/// within the underlying allocation it is a sub-view of. Returns the raw buffer handle and the
+/// absolute offset (within the underlying allocation) to write at.
+fn validate_update_dest<B>(buffer: &B, size: usize)
+                            -> Result<(vk::Buffer, vk::DeviceSize), CmdUpdateBufferError>
+    where B: BufferAccess
+{
+    let view_size = buffer.size();
+
+    let (buffer_handle, offset, buffer_inner_size) = {
+        let BufferInner { buffer: buffer_inner, offset } = buffer.inner();
+        if !buffer_inner.usage_transfer_dest() {
+            return Err(CmdUpdateBufferError::BufferMissingUsage);
+        }
+        if offset % 4 != 0 {
+            return Err(CmdUpdateBufferError::WrongAlignment);
+        }
+        (buffer_inner.internal_object(), offset, buffer_inner.size())
+    };
+
+    if size % 4 != 0 {
+        return Err(CmdUpdateBufferError::WrongAlignment);
+    }
+
+    if size > UPDATE_BUFFER_MAX_SIZE {
+        return Err(CmdUpdateBufferError::DataTooLarge);
+    }
+
+    // `size` must stay within this `BufferAccess`'s own declared view, not just the (possibly
+    // larger) underlying allocation it is a sub-view of, otherwise the write could spill into
+    // whatever else lives in the shared buffer.
+    if size > view_size {
+        return Err(CmdUpdateBufferError::OutOfBounds);
+    }
+
+    // `offset` and `size` may come from independently-computed values, so guard against the
+    // addition overflowing as well as against it landing outside of the buffer.
+    let end = offset.checked_add(size).ok_or(CmdUpdateBufferError::OutOfBounds)?;
+    if end > buffer_inner_size {
+        return Err(CmdUpdateBufferError::OutOfBounds);
+    }
+
+    Ok((buffer_handle, offset as vk::DeviceSize))
+}
+
 /// Command that sets the content of a buffer to some data.
 pub struct CmdUpdateBuffer<B, D> {
     // The buffer to update.
@@ -76,32 +130,144 @@ impl<B, D> CmdUpdateBuffer<B, D> {
         where B: BufferAccess
     {
         let size = buffer.size();
+        let (buffer_handle, offset) = validate_update_dest(&buffer, size)?;
 
-        let (buffer_handle, offset) = {
-            let BufferInner { buffer: buffer_inner, offset } = buffer.inner();
-            if !buffer_inner.usage_transfer_dest() {
-                return Err(CmdUpdateBufferError::BufferMissingUsage);
-            }
-            if offset % 4 != 0 {
-                return Err(CmdUpdateBufferError::WrongAlignment);
-            }
-            (buffer_inner.internal_object(), offset)
-        };
-
-        if size % 4 != 0 {
-            return Err(CmdUpdateBufferError::WrongAlignment);
+        Ok(CmdUpdateBuffer {
+            buffer: buffer,
+            buffer_handle: buffer_handle,
+            offset: offset,
+            size: size as vk::DeviceSize,
+            data: data,
+        })
+    }
+
+    /// Returns the buffer that is going to be written.
+    #[inline]
+    pub fn buffer(&self) -> &B {
+        &self.buffer
+    }
+}
+
+impl<B, D> CmdUpdateBuffer<B, D> {
+    /// Same as `new`, except that `data` is allowed to be larger than the 65536-byte limit
+    /// imposed by `vkCmdUpdateBuffer`.
+    ///
+    /// If the data fits within the limit, this is equivalent to `new` and uses the cheap
+    /// inline update path. Otherwise a transient host-visible staging buffer is allocated,
+    /// `data` is written into it, and a buffer-to-buffer copy is emitted instead.
+    #[inline]
+    pub fn new_large<P>(buffer: P, data: D)
+                         -> Result<CmdUpdateBufferLarge<B, D>, CmdUpdateBufferError>
+        where P: Buffer<Access = B> + TypedBuffer<Content = D>,
+              B: BufferAccess + DeviceOwned + TypedBufferAccess<Content = D>,
+              D: 'static
+    {
+        unsafe {
+            CmdUpdateBuffer::unchecked_type_large(buffer.access(), data)
         }
+    }
 
-        if size > 65536 {
-            return Err(CmdUpdateBufferError::DataTooLarge);
+    /// Same as `new_large`, except that type safety is not enforced.
+    pub unsafe fn unchecked_type_large(buffer: B, data: D)
+                                        -> Result<CmdUpdateBufferLarge<B, D>, CmdUpdateBufferError>
+        where B: BufferAccess + DeviceOwned + TypedBufferAccess<Content = D>,
+              D: 'static
+    {
+        if mem::size_of::<D>() <= UPDATE_BUFFER_MAX_SIZE {
+            return CmdUpdateBuffer::unchecked_type(buffer, data)
+                       .map(CmdUpdateBufferLarge::Inline);
         }
 
-        Ok(CmdUpdateBuffer {
+        let device = buffer.device().clone();
+
+        let staging = CpuAccessibleBuffer::from_data(device, BufferUsage::transfer_source(), data)
+            .map_err(|_| CmdUpdateBufferError::StagingBufferCreationFailed)?;
+
+        let copy = CmdCopyBuffer::new(staging, buffer)
+            .map_err(|_| CmdUpdateBufferError::CopyFailed)?;
+
+        Ok(CmdUpdateBufferLarge::Staged(copy))
+    }
+}
+
+/// Command returned by `CmdUpdateBuffer::new_large`, which is either an inline
+/// `CmdUpdateBuffer` or a copy from a staging buffer, depending on the size of the data.
+pub enum CmdUpdateBufferLarge<B, D>
+    where D: 'static
+{
+    Inline(CmdUpdateBuffer<B, D>),
+    Staged(CmdCopyBuffer<Arc<CpuAccessibleBuffer<D>>, B>),
+}
+
+/// Command that sets the content of a buffer to `data`, whose size is rounded up to the next
+/// multiple of four.
+///
+/// Unlike `CmdUpdateBuffer`, which rejects any `data` whose size isn't already a multiple of
+/// four, this pads the transfer with zeroed trailing bytes so that odd-sized types can still be
+/// uploaded with `vkCmdUpdateBuffer`. The extra bytes are written into the destination buffer,
+/// so it must have room for the rounded-up size.
+pub struct CmdUpdateBufferPadded<B> {
+    // The buffer to update.
+    buffer: B,
+    // Raw buffer handle.
+    buffer_handle: vk::Buffer,
+    // Offset of the update.
+    offset: vk::DeviceSize,
+    // Size of the update, rounded up to a multiple of four.
+    size: vk::DeviceSize,
+    // `data`'s bytes, zero-padded up to `size`. Kept separate from `D` so that the
+    // `vkCmdUpdateBuffer` call never reads past the end of the original value.
+    data: Box<[u8]>,
+}
+
+impl<B> CmdUpdateBufferPadded<B> {
+    /// Builds a command that writes `data` to a buffer, rounding the transfer size up to the
+    /// next multiple of four and zero-filling the extra bytes.
+    #[inline]
+    pub fn new<P, D>(buffer: P, data: D) -> Result<CmdUpdateBufferPadded<B>, CmdUpdateBufferError>
+        where P: Buffer<Access = B> + TypedBuffer<Content = D>,
+              B: BufferAccess,
+              D: 'static
+    {
+        unsafe {
+            CmdUpdateBufferPadded::unchecked_type(buffer.access(), data)
+        }
+    }
+
+    /// Same as `new`, except that the parameter is a `BufferAccess` instead of a `Buffer`.
+    #[inline]
+    pub fn from_access<D>(buffer: B, data: D)
+                           -> Result<CmdUpdateBufferPadded<B>, CmdUpdateBufferError>
+        where B: BufferAccess + TypedBufferAccess<Content = D>,
+              D: 'static
+    {
+        unsafe {
+            CmdUpdateBufferPadded::unchecked_type(buffer, data)
+        }
+    }
+
+    /// Same as `from_access`, except that type safety is not enforced.
+    pub unsafe fn unchecked_type<D>(buffer: B, data: D)
+                                     -> Result<CmdUpdateBufferPadded<B>, CmdUpdateBufferError>
+        where B: BufferAccess
+    {
+        let padded_size = (mem::size_of::<D>() + 3) / 4 * 4;
+        let (buffer_handle, offset) = validate_update_dest(&buffer, padded_size)?;
+
+        // Zero the padding up front, then copy over only the bytes that actually belong to
+        // `data`, so the trailing bytes we never read from `D` are well-defined zeroes instead
+        // of uninitialized memory.
+        let mut padded = vec![0u8; padded_size].into_boxed_slice();
+        ptr::copy_nonoverlapping(&data as *const D as *const u8,
+                                  padded.as_mut_ptr(),
+                                  mem::size_of::<D>());
+
+        Ok(CmdUpdateBufferPadded {
             buffer: buffer,
             buffer_handle: buffer_handle,
-            offset: offset as vk::DeviceSize,
-            size: size as vk::DeviceSize,
-            data: data,
+            offset: offset,
+            size: padded_size as vk::DeviceSize,
+            data: padded,
         })
     }
 
@@ -112,6 +278,34 @@ impl<B, D> CmdUpdateBuffer<B, D> {
     }
 }
 
+unsafe impl<B> DeviceOwned for CmdUpdateBufferPadded<B>
+    where B: DeviceOwned
+{
+    #[inline]
+    fn device(&self) -> &Arc<Device> {
+        self.buffer.device()
+    }
+}
+
+unsafe impl<'a, P, B> AddCommand<&'a CmdUpdateBufferPadded<B>> for UnsafeCommandBufferBuilder<P>
+    where B: BufferAccess,
+          P: CommandPool,
+{
+    type Out = UnsafeCommandBufferBuilder<P>;
+
+    #[inline]
+    fn add(self, command: &'a CmdUpdateBufferPadded<B>) -> Result<Self::Out, CommandAddError> {
+        unsafe {
+            let data = command.data.as_ptr() as *const _;
+            let vk = self.device().pointers();
+            let cmd = self.internal_object();
+            vk.CmdUpdateBuffer(cmd, command.buffer_handle, command.offset, command.size, data);
+        }
+
+        Ok(self)
+    }
+}
+
 unsafe impl<B, D> DeviceOwned for CmdUpdateBuffer<B, D>
     where B: DeviceOwned
 {
@@ -140,6 +334,23 @@ unsafe impl<'a, P, B, D> AddCommand<&'a CmdUpdateBuffer<B, D>> for UnsafeCommand
     }
 }
 
+unsafe impl<'a, P, B, D> AddCommand<&'a CmdUpdateBufferLarge<B, D>> for UnsafeCommandBufferBuilder<P>
+    where B: BufferAccess,
+          P: CommandPool,
+          UnsafeCommandBufferBuilder<P>: AddCommand<&'a CmdUpdateBuffer<B, D>, Out = UnsafeCommandBufferBuilder<P>> +
+                                          AddCommand<&'a CmdCopyBuffer<Arc<CpuAccessibleBuffer<D>>, B>, Out = UnsafeCommandBufferBuilder<P>>,
+{
+    type Out = UnsafeCommandBufferBuilder<P>;
+
+    #[inline]
+    fn add(self, command: &'a CmdUpdateBufferLarge<B, D>) -> Result<Self::Out, CommandAddError> {
+        match *command {
+            CmdUpdateBufferLarge::Inline(ref cmd) => self.add(cmd),
+            CmdUpdateBufferLarge::Staged(ref cmd) => self.add(cmd),
+        }
+    }
+}
+
 /// Error that can happen when creating a `CmdUpdateBuffer`.
 #[derive(Debug, Copy, Clone)]
 pub enum CmdUpdateBufferError {
@@ -149,6 +360,13 @@ pub enum CmdUpdateBufferError {
     WrongAlignment,
     /// The data must not be larger than 64k bytes.
     DataTooLarge,
+    /// The size doesn't fit within the buffer, or the offset and size, once added together,
+    /// overflow or don't fit within the buffer's allocation.
+    OutOfBounds,
+    /// Failed to allocate or fill the staging buffer used to upload data larger than 64k bytes.
+    StagingBufferCreationFailed,
+    /// The buffer-to-buffer copy used to apply a staged update was rejected.
+    CopyFailed,
 }
 
 impl error::Error for CmdUpdateBufferError {
@@ -162,6 +380,15 @@ impl error::Error for CmdUpdateBufferError {
                 "the offset or size are not aligned to 4 bytes"
             },
             CmdUpdateBufferError::DataTooLarge => "data is too large",
+            CmdUpdateBufferError::OutOfBounds => {
+                "the offset and size are out of the buffer's range"
+            },
+            CmdUpdateBufferError::StagingBufferCreationFailed => {
+                "failed to create the staging buffer for a large update"
+            },
+            CmdUpdateBufferError::CopyFailed => {
+                "the buffer-to-buffer copy used to apply a staged update was rejected"
+            },
         }
     }
 }